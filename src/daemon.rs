@@ -0,0 +1,303 @@
+//! Long-running daemon mode.
+//!
+//! Keeps the force-feedback device open and serves vibration requests over
+//! a Unix control socket, so callers don't pay the per-call cost of opening
+//! the device. Each request is still uploaded as its own effect and removed
+//! once it has finished playing. An epoll context watches
+//! the listening socket, accepted connections and an eventfd "kill" handle,
+//! so the worker loop can be shut down cleanly from a signal handler.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Duration;
+use std::{env, fs, io, mem, ptr};
+
+use crate::scheduler::Scheduler;
+use crate::{EffectParams, Vibrator, Waveform};
+
+/// Socket file name created under `$XDG_RUNTIME_DIR`.
+const SOCKET_NAME: &str = "tremor.sock";
+
+/// Wire format for a single vibration request: `length:u16`, `interval:u16`,
+/// `count:u16`, `waveform:u8` (0 = rumble, 1-5 = [`Waveform`] variants),
+/// `magnitude:u16` (strong rumble motor; the weak motor is left at 0) and
+/// `delay:u16` (start offset from now), all little-endian.
+const REQUEST_LEN: usize = 11;
+
+/// epoll token identifying which fd became readable.
+const TOKEN_LISTENER: u64 = 0;
+const TOKEN_KILL: u64 = 1;
+
+/// Bit set on every accepted connection's epoll token, distinguishing it
+/// from the reserved listener/kill sentinels above so the low bits can
+/// carry the connection's fd.
+const CONNECTION_TOKEN_BIT: u64 = 1 << 32;
+
+/// Raw fd of the kill eventfd, written to from a signal handler.
+static KILL_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// Default control socket path, under `$XDG_RUNTIME_DIR` (or `/tmp` when
+/// that variable is unset).
+pub fn default_socket_path() -> PathBuf {
+    let runtime_dir = env::var_os("XDG_RUNTIME_DIR").unwrap_or_else(|| "/tmp".into());
+    PathBuf::from(runtime_dir).join(SOCKET_NAME)
+}
+
+/// A single vibration request received over the control socket.
+struct Request {
+    params: EffectParams,
+    count: u16,
+    delay: Duration,
+}
+
+impl Request {
+    fn decode(buf: &[u8; REQUEST_LEN]) -> Option<Self> {
+        let length = u16::from_le_bytes([buf[0], buf[1]]);
+        let interval = u16::from_le_bytes([buf[2], buf[3]]);
+        let count = u16::from_le_bytes([buf[4], buf[5]]);
+        let waveform = match buf[6] {
+            0 => None,
+            1 => Some(Waveform::Square),
+            2 => Some(Waveform::Triangle),
+            3 => Some(Waveform::Sine),
+            4 => Some(Waveform::SawUp),
+            5 => Some(Waveform::SawDown),
+            _ => return None,
+        };
+        let magnitude = u16::from_le_bytes([buf[7], buf[8]]);
+        let delay = u16::from_le_bytes([buf[9], buf[10]]);
+
+        // Waveforms played over the socket cycle once per effect duration;
+        // finer control arrives via the one-shot `--period` flag instead.
+        let period = length;
+        let params =
+            EffectParams { length, interval, strong: magnitude, weak: 0, waveform, period, attack: 0, fade: 0 };
+
+        Some(Self { params, count, delay: Duration::from_millis(delay as u64) })
+    }
+}
+
+/// An accepted connection with its request buffered across non-blocking
+/// reads, so a client that trickles or never sends its frame only ever
+/// blocks its own epoll readiness, not the worker loop.
+struct Connection {
+    stream: UnixStream,
+    buf: [u8; REQUEST_LEN],
+    filled: usize,
+}
+
+/// Run the daemon worker loop until killed by `SIGINT`/`SIGTERM`.
+///
+/// Requests are scheduled onto a pool of up to [`Vibrator::effect_limit`]
+/// concurrently uploaded effects, so overlapping callers play independently
+/// instead of clobbering each other's effects.
+pub fn run(mut vibrator: Vibrator, socket_path: &Path) -> Result<(), String> {
+    if socket_path.exists() {
+        fs::remove_file(socket_path)
+            .map_err(|err| format!("Failed to remove stale socket {socket_path:?}: {err}"))?;
+    }
+
+    let listener = UnixListener::bind(socket_path)
+        .map_err(|err| format!("Failed to bind socket {socket_path:?}: {err}"))?;
+    listener
+        .set_nonblocking(true)
+        .map_err(|err| format!("Failed to configure socket {socket_path:?}: {err}"))?;
+
+    let kill = create_eventfd().map_err(|err| format!("Failed to create kill eventfd: {err}"))?;
+    install_signal_handlers(kill.as_raw_fd())
+        .map_err(|err| format!("Failed to install signal handlers: {err}"))?;
+
+    let epoll = create_epoll().map_err(|err| format!("Failed to create epoll context: {err}"))?;
+    epoll_add(epoll.as_raw_fd(), listener.as_raw_fd(), TOKEN_LISTENER)
+        .map_err(|err| format!("Failed to watch socket: {err}"))?;
+    epoll_add(epoll.as_raw_fd(), kill.as_raw_fd(), TOKEN_KILL)
+        .map_err(|err| format!("Failed to watch kill eventfd: {err}"))?;
+
+    let capacity = vibrator.effect_limit().unwrap_or(1);
+    let mut scheduler = Scheduler::new(capacity);
+    let mut connections: HashMap<RawFd, Connection> = HashMap::new();
+    let mut events = [libc::epoll_event { events: 0, u64: 0 }; 16];
+
+    'worker: loop {
+        let next_deadline = scheduler.tick(&mut vibrator);
+        let timeout_ms = match next_deadline {
+            Some(duration) => duration.as_millis().min(i32::MAX as u128) as i32,
+            None => -1,
+        };
+
+        let ready = epoll_wait(epoll.as_raw_fd(), &mut events, timeout_ms)
+            .map_err(|err| format!("epoll_wait failed: {err}"))?;
+
+        for event in &events[..ready] {
+            match event.u64 {
+                TOKEN_LISTENER => accept_connections(&listener, epoll.as_raw_fd(), &mut connections),
+                TOKEN_KILL => break 'worker,
+                token => {
+                    let fd = (token & !CONNECTION_TOKEN_BIT) as RawFd;
+                    service_connection(fd, epoll.as_raw_fd(), &mut connections, &mut vibrator, &mut scheduler);
+                },
+            }
+        }
+    }
+
+    let _ = fs::remove_file(socket_path);
+
+    Ok(())
+}
+
+/// Accept every pending connection and register it with `epoll` for
+/// non-blocking reads, so a slow or silent client only ever stalls its own
+/// connection instead of the whole worker loop.
+fn accept_connections(listener: &UnixListener, epoll_fd: RawFd, connections: &mut HashMap<RawFd, Connection>) {
+    loop {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+            Err(err) => {
+                eprintln!("Warn: Failed to accept connection: {err}");
+                return;
+            },
+        };
+
+        if let Err(err) = stream.set_nonblocking(true) {
+            eprintln!("Warn: Failed to configure accepted connection: {err}");
+            continue;
+        }
+
+        let fd = stream.as_raw_fd();
+        if let Err(err) = epoll_add(epoll_fd, fd, fd as u64 | CONNECTION_TOKEN_BIT) {
+            eprintln!("Warn: Failed to watch accepted connection: {err}");
+            continue;
+        }
+
+        connections.insert(fd, Connection { stream, buf: [0; REQUEST_LEN], filled: 0 });
+    }
+}
+
+/// Read more of `fd`'s buffered request, scheduling it and closing the
+/// connection once a full frame has arrived.
+fn service_connection(
+    fd: RawFd,
+    epoll_fd: RawFd,
+    connections: &mut HashMap<RawFd, Connection>,
+    vibrator: &mut Vibrator,
+    scheduler: &mut Scheduler,
+) {
+    let Some(connection) = connections.get_mut(&fd) else { return };
+
+    loop {
+        match connection.stream.read(&mut connection.buf[connection.filled..]) {
+            Ok(0) => {
+                eprintln!("Warn: Connection closed before a full request arrived");
+                break;
+            },
+            Ok(read) => connection.filled += read,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+            Err(err) => {
+                eprintln!("Warn: Failed to read request: {err}");
+                break;
+            },
+        }
+
+        if connection.filled < REQUEST_LEN {
+            continue;
+        }
+
+        match Request::decode(&connection.buf) {
+            Some(request) => {
+                if let Err(err) = scheduler.schedule(vibrator, request.params, request.count, request.delay) {
+                    eprintln!("Warn: Failed to schedule request: {err}");
+                }
+            },
+            None => eprintln!("Warn: Dropping malformed request"),
+        }
+        break;
+    }
+
+    let _ = epoll_del(epoll_fd, fd);
+    connections.remove(&fd);
+}
+
+fn create_epoll() -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn create_eventfd() -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn epoll_add(epoll_fd: RawFd, fd: RawFd, token: u64) -> io::Result<()> {
+    let mut event = libc::epoll_event { events: libc::EPOLLIN as u32, u64: token };
+    let ret = unsafe { libc::epoll_ctl(epoll_fd, libc::EPOLL_CTL_ADD, fd, &mut event) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn epoll_del(epoll_fd: RawFd, fd: RawFd) -> io::Result<()> {
+    let ret = unsafe { libc::epoll_ctl(epoll_fd, libc::EPOLL_CTL_DEL, fd, ptr::null_mut()) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn epoll_wait(epoll_fd: RawFd, events: &mut [libc::epoll_event], timeout_ms: i32) -> io::Result<usize> {
+    let ready = unsafe { libc::epoll_wait(epoll_fd, events.as_mut_ptr(), events.len() as i32, timeout_ms) };
+    if ready < 0 {
+        let err = io::Error::last_os_error();
+        // A shutdown signal interrupts the wait after its handler has written
+        // to the kill eventfd, which the next wait then reports.
+        if err.kind() == io::ErrorKind::Interrupted {
+            return Ok(0);
+        }
+        return Err(err);
+    }
+    Ok(ready as usize)
+}
+
+/// Install `SIGINT`/`SIGTERM` handlers that signal the worker loop by
+/// writing to the kill eventfd, the only async-signal-safe way to wake an
+/// epoll context from a handler.
+fn install_signal_handlers(kill_fd: RawFd) -> io::Result<()> {
+    KILL_FD.store(kill_fd, Ordering::Relaxed);
+
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handle_shutdown_signal as *const () as usize;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        if libc::sigaction(libc::SIGINT, &action, ptr::null_mut()) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if libc::sigaction(libc::SIGTERM, &action, ptr::null_mut()) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+extern "C" fn handle_shutdown_signal(_signal: libc::c_int) {
+    let fd = KILL_FD.load(Ordering::Relaxed);
+    if fd < 0 {
+        return;
+    }
+    let value: u64 = 1;
+    unsafe {
+        libc::write(fd, (&value as *const u64).cast(), mem::size_of::<u64>());
+    }
+}
@@ -1,23 +1,71 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::time::Duration;
-use std::{fmt, io, mem, process, slice, thread};
+use std::{fmt, io, mem, process, ptr, slice, thread};
 
 use argh::FromArgs;
-use nix::{ioctl_write_int, ioctl_write_ptr};
+use nix::{ioctl_read, ioctl_read_buf, ioctl_write_int, ioctl_write_ptr};
+
+mod daemon;
+mod scheduler;
 
 /// Force-feedback device path.
+///
+/// Used as a fallback when neither `--device-path` nor `--name` is given and
+/// auto-discovery fails to find a suitable device.
 const DEVICE_PATH: &str = "/dev/input/by-path/platform-vibrator-event";
 
+/// Directory scanned for candidate force-feedback devices.
+const DEV_INPUT_DIR: &str = "/dev/input";
+
 /// Force-feedback event type constant.
 /// <https://github.com/torvalds/linux/blob/9f4211bf7f811b653aa6acfb9aea38222436a458/include/uapi/linux/input-event-codes.h#L47>
 const EV_FF: u16 = 0x15;
 
+/// Highest valid event type, used to size the `EVIOCGBIT(0, ...)` bitmap.
+const EV_MAX: u16 = 0x1f;
+
+/// Rumble force-feedback effect.
+const FF_RUMBLE: u16 = 0x50;
+
+/// Periodic waveform force-feedback effect.
+const FF_PERIODIC: u16 = 0x51;
+
+/// Highest valid force-feedback effect type, used to size the
+/// `EVIOCGBIT(EV_FF, ...)` bitmap.
+const FF_MAX: u16 = 0x7f;
+
+/// Overall output gain force-feedback effect.
+const FF_GAIN: u16 = 0x60;
+
 /// Force-feedback device control utility.
-#[derive(FromArgs, Default)]
+#[derive(FromArgs)]
 pub struct Cli {
+    #[argh(subcommand)]
+    command: Command,
+    /// force-feedback device path; auto-discovered when omitted
+    #[argh(option)]
+    device_path: Option<PathBuf>,
+    /// substring to match against the device name during auto-discovery
+    #[argh(option)]
+    name: Option<String>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Vibrate(VibrateCommand),
+    Daemon(DaemonCommand),
+    Info(InfoCommand),
+}
+
+/// Play a single rumble or periodic waveform effect and exit.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "vibrate")]
+struct VibrateCommand {
     /// duration of each vibration in milliseconds
     #[argh(positional)]
     length: u16,
@@ -27,103 +75,444 @@ pub struct Cli {
     /// number of vibrations
     #[argh(positional)]
     count: u16,
-    /// force-feedback device path
+    /// play a periodic waveform instead of a flat rumble
     #[argh(option)]
-    device_path: Option<PathBuf>,
+    waveform: Option<Waveform>,
+    /// waveform period in milliseconds
+    #[argh(option, default = "0")]
+    period: u16,
+    /// envelope attack length in milliseconds, ramping intensity up from zero
+    #[argh(option, default = "0")]
+    attack: u16,
+    /// envelope fade length in milliseconds, ramping intensity down to zero
+    #[argh(option, default = "0")]
+    fade: u16,
+    /// strong rumble motor magnitude (0-65535)
+    #[argh(option, default = "u16::MAX")]
+    strong: u16,
+    /// weak rumble motor magnitude (0-65535)
+    #[argh(option, default = "0")]
+    weak: u16,
+    /// overall output gain (0-65535), applied via `FF_GAIN` before playing
+    #[argh(option)]
+    gain: Option<u16>,
+}
+
+/// Serve vibration requests over a Unix control socket instead of playing a
+/// single effect and exiting.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "daemon")]
+struct DaemonCommand {
+    /// control socket path; defaults under `$XDG_RUNTIME_DIR`
+    #[argh(option)]
+    socket_path: Option<PathBuf>,
+}
+
+/// Report the device's name and force-feedback capabilities.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "info")]
+struct InfoCommand {}
+
+/// Periodic waveform shape, matching the kernel's `FF_*` waveform constants.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Waveform {
+    Square,
+    Triangle,
+    Sine,
+    SawUp,
+    SawDown,
+}
+
+impl Waveform {
+    /// Kernel `FF_*` waveform constant for this shape.
+    fn code(self) -> u16 {
+        match self {
+            Self::Square => 0x58,
+            Self::Triangle => 0x59,
+            Self::Sine => 0x5a,
+            Self::SawUp => 0x5b,
+            Self::SawDown => 0x5c,
+        }
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "square" => Ok(Self::Square),
+            "triangle" => Ok(Self::Triangle),
+            "sine" => Ok(Self::Sine),
+            "saw-up" => Ok(Self::SawUp),
+            "saw-down" => Ok(Self::SawDown),
+            _ => Err(format!("invalid waveform {s:?}, expected one of: square, triangle, sine, saw-up, saw-down")),
+        }
+    }
 }
 
 fn main() {
     let cli: Cli = argh::from_env();
 
-    let path = cli.device_path.unwrap_or_else(|| DEVICE_PATH.into());
+    let mut discovery_err = None;
+    let path = match &cli.device_path {
+        Some(path) => path.clone(),
+        // Only fall back to the legacy hardcoded path when the user didn't
+        // ask for a specific device; an explicit `--name` filter with no
+        // match is more likely a typo than a missing fallback device.
+        None => match find_device(cli.name.as_deref()) {
+            Ok(path) => path,
+            Err(err) if cli.name.is_none() => {
+                eprintln!("Warn: {err}, falling back to {DEVICE_PATH}");
+                discovery_err = Some(err);
+                DEVICE_PATH.into()
+            },
+            Err(err) => {
+                eprintln!("Error: Could not find a rumble device: {err}");
+                process::exit(1);
+            },
+        },
+    };
+
     let mut vibrator = match Vibrator::new(&path) {
         Ok(vibrator) => vibrator,
-        Err(err) => {
-            eprintln!("Error: Could not open device {path:?}: {err}");
-            process::exit(1);
+        Err(err) => match discovery_err {
+            Some(discovery_err) => {
+                eprintln!("Error: Could not open device {path:?}: {err} (auto-discovery also failed: {discovery_err})");
+                process::exit(1);
+            },
+            None => {
+                eprintln!("Error: Could not open device {path:?}: {err}");
+                process::exit(1);
+            },
         },
     };
 
-    match vibrator.vibrate(cli.length, cli.interval, cli.count) {
-        Ok(()) => (),
-        Err(err) => {
-            eprintln!("Error: Failed to play rumble effect: {err}");
+    match cli.command {
+        Command::Daemon(daemon) => {
+            let socket_path = daemon.socket_path.unwrap_or_else(daemon::default_socket_path);
+            if let Err(err) = daemon::run(vibrator, &socket_path) {
+                eprintln!("Error: {err}");
+                process::exit(1);
+            }
+        },
+        Command::Vibrate(vibrate) => {
+            if let Some(gain) = vibrate.gain {
+                if let Err(err) = vibrator.set_gain(gain) {
+                    eprintln!("Error: Failed to set gain: {err}");
+                    process::exit(1);
+                }
+            }
+
+            let params = EffectParams {
+                length: vibrate.length,
+                interval: vibrate.interval,
+                strong: vibrate.strong,
+                weak: vibrate.weak,
+                waveform: vibrate.waveform,
+                period: vibrate.period,
+                attack: vibrate.attack,
+                fade: vibrate.fade,
+            };
+            if let Err(err) = vibrator.vibrate(params, vibrate.count) {
+                eprintln!("Error: Failed to play rumble effect: {err}");
+            }
+        },
+        Command::Info(_) => match vibrator.info() {
+            Ok(info) => {
+                println!("Device: {}", info.name);
+                println!("Max simultaneous effects: {}", info.effect_limit);
+                println!("Rumble: {}", if info.rumble { "yes" } else { "no" });
+                println!("Periodic waveforms: {}", if info.periodic { "yes" } else { "no" });
+                println!("Gain control: {}", if info.gain { "yes" } else { "no" });
+            },
+            Err(err) => {
+                eprintln!("Error: Could not query device info: {err}");
+                process::exit(1);
+            },
         },
     }
 }
 
+/// Scan `/dev/input` for a force-feedback device supporting rumble effects.
+///
+/// Every `eventN` node is opened and queried for its name and supported
+/// event/effect bits; the first node advertising `EV_FF` with the
+/// `FF_RUMBLE` bit set is returned. Nodes that cannot be opened due to
+/// permissions are skipped. When `name_filter` is set, only devices whose
+/// name contains it (case-insensitively) are considered.
+fn find_device(name_filter: Option<&str>) -> Result<PathBuf, io::Error> {
+    let mut scanned = Vec::new();
+
+    for entry in fs::read_dir(DEV_INPUT_DIR)? {
+        let path = entry?.path();
+        if !path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with("event")) {
+            continue;
+        }
+        scanned.push(path.clone());
+
+        let device = match File::options().read(true).write(true).open(&path) {
+            Ok(device) => device,
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied => continue,
+            Err(err) => {
+                eprintln!("Warn: Failed to open {path:?}: {err}");
+                continue;
+            },
+        };
+        let fd = device.as_raw_fd();
+
+        let mut name = [0u8; 256];
+        if unsafe { eviocgname(fd, &mut name) }.is_err() {
+            continue;
+        }
+        let name_end = name.iter().position(|&byte| byte == 0).unwrap_or(name.len());
+        let name = String::from_utf8_lossy(&name[..name_end]);
+
+        if let Some(filter) = name_filter {
+            if !name.to_lowercase().contains(&filter.to_lowercase()) {
+                continue;
+            }
+        }
+
+        let mut ev_bits = [0u8; bitmap_len(EV_MAX)];
+        if unsafe { eviocgbit_ev(fd, &mut ev_bits) }.is_err() || !test_bit(&ev_bits, EV_FF) {
+            continue;
+        }
+
+        let mut ff_bits = [0u8; bitmap_len(FF_MAX)];
+        if unsafe { eviocgbit_ff(fd, &mut ff_bits) }.is_err() || !test_bit(&ff_bits, FF_RUMBLE) {
+            continue;
+        }
+
+        return Ok(path);
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no rumble-capable device found among {scanned:?}"),
+    ))
+}
+
+/// Number of bytes required to hold a bitmap covering `0..=max_bit`.
+const fn bitmap_len(max_bit: u16) -> usize {
+    (max_bit as usize / 8) + 1
+}
+
+/// Check whether `bit` is set in an `EVIOCGBIT` bitmap.
+fn test_bit(bitmap: &[u8], bit: u16) -> bool {
+    let byte = bit as usize / 8;
+    byte < bitmap.len() && bitmap[byte] & (1 << (bit % 8)) != 0
+}
+
+/// Parameters defining a rumble or periodic waveform effect, used to decide
+/// whether an already-uploaded effect can be reused as-is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct EffectParams {
+    pub length: u16,
+    pub interval: u16,
+    pub strong: u16,
+    pub weak: u16,
+    pub waveform: Option<Waveform>,
+    pub period: u16,
+    pub attack: u16,
+    pub fade: u16,
+}
+
+impl EffectParams {
+    /// Build the `Effect` this configuration uploads as, reusing `id` so
+    /// the kernel updates the existing slot instead of allocating a new one.
+    fn to_effect(self, id: i16) -> Effect {
+        let (effect_type, data) = match self.waveform {
+            Some(waveform) => {
+                let periodic = Periodic {
+                    waveform: waveform.code(),
+                    period: self.period,
+                    // `ff_periodic_effect.magnitude` is a signed i16, unlike
+                    // `Rumble`'s full-u16-range `strong`, so clamp instead of
+                    // wrapping `u16::MAX` (the `--strong` default) to -1.
+                    magnitude: self.strong.min(i16::MAX as u16) as i16,
+                    offset: 0,
+                    phase: 0,
+                    envelope: Envelope {
+                        attack_length: self.attack,
+                        attack_level: 0,
+                        fade_length: self.fade,
+                        fade_level: 0,
+                    },
+                    custom_len: 0,
+                    custom_data: ptr::null_mut(),
+                };
+                (FF_PERIODIC, EffectData { periodic })
+            },
+            None => (FF_RUMBLE, EffectData { rumble: Rumble { strong: self.strong, weak: self.weak } }),
+        };
+
+        Effect {
+            effect_type,
+            id,
+            direction: 0,
+            trigger: Trigger { interval: 0, button: 0 },
+            replay: Replay { length: self.length, delay: self.interval },
+            data,
+        }
+    }
+}
+
+/// Device capability summary reported by the `info` subcommand.
+pub(crate) struct DeviceInfo {
+    pub name: String,
+    pub rumble: bool,
+    pub periodic: bool,
+    pub gain: bool,
+    pub effect_limit: usize,
+}
+
 /// Force-feedback interface.
+///
+/// Keeps a single reusable effect uploaded for the lifetime of the
+/// [`Vibrator`]: calls with unchanged parameters just replay it, and only
+/// a parameter change triggers a re-upload of the same effect `id`.
+/// Callers managing several effects at once (e.g. the [`scheduler`]) can
+/// bypass this cache via [`Self::upload`] directly.
 pub struct Vibrator {
     device: File,
+    effect_id: i16,
+    params: Option<EffectParams>,
 }
 
 impl Vibrator {
     fn new(device_path: &Path) -> Result<Self, io::Error> {
-        Ok(Self { device: File::options().append(true).open(device_path)? })
+        Ok(Self { device: File::options().append(true).open(device_path)?, effect_id: -1, params: None })
+    }
+
+    /// Maximum number of effects the device can hold uploaded simultaneously.
+    pub(crate) fn effect_limit(&self) -> Result<usize, String> {
+        let mut limit = 0;
+        unsafe { eviocgeffects(self.device.as_raw_fd(), &mut limit) }
+            .map_err(|err| format!("Failed to query effect limit: {err}"))?;
+        Ok(limit.max(0) as usize)
     }
 
-    /// Stop vibration and remove effect from device.
-    fn stop(&mut self, effect_id: u64) -> Result<(), String> {
+    /// Upload `params` to the device, reusing `id` when it already refers
+    /// to an uploaded effect so the kernel updates it in place. Returns the
+    /// (possibly newly-assigned) effect id.
+    pub(crate) fn upload(&mut self, id: i16, params: EffectParams) -> Result<i16, String> {
+        let mut effect = params.to_effect(id);
+
+        match unsafe { upload_effect(self.device.as_raw_fd(), &mut effect as *const _) } {
+            Err(err) => Err(format!("Failed to upload effect: {err}")),
+            Ok(_) if effect.id < 0 => Err(format!("Invalid effect ID: {effect:?}")),
+            Ok(_) => Ok(effect.id),
+        }
+    }
+
+    /// Play an already-uploaded effect `count` times.
+    pub(crate) fn replay(&mut self, id: i16, count: u16) -> Result<(), String> {
+        self.write_ff_event(id as u16, count as i32)
+    }
+
+    /// Scale overall force-feedback output by `gain` (0-65535), applying to
+    /// every effect played afterwards until changed again.
+    pub(crate) fn set_gain(&mut self, gain: u16) -> Result<(), String> {
+        self.write_ff_event(FF_GAIN, gain as i32)
+    }
+
+    /// Write a raw `EV_FF` event with the given code and value.
+    fn write_ff_event(&mut self, code: u16, value: i32) -> Result<(), String> {
+        let event = libc::input_event {
+            time: libc::timeval { tv_sec: 0, tv_usec: 0 },
+            code,
+            value,
+            type_: EV_FF,
+        };
+        let event_ptr = (&event as *const libc::input_event).cast();
+        let event_size = mem::size_of::<libc::input_event>();
+        let event_data = unsafe { slice::from_raw_parts(event_ptr, event_size) };
+        self.device.write(event_data).map_err(|err| format!("Failed to submit force-feedback event: {err}"))?;
+        Ok(())
+    }
+
+    /// Query the device's name and force-feedback capabilities.
+    pub(crate) fn info(&self) -> Result<DeviceInfo, String> {
         let fd = self.device.as_raw_fd();
-        match unsafe { remove_effect(fd, effect_id) } {
+
+        let mut name = [0u8; 256];
+        unsafe { eviocgname(fd, &mut name) }.map_err(|err| format!("Failed to query device name: {err}"))?;
+        let name_end = name.iter().position(|&byte| byte == 0).unwrap_or(name.len());
+        let name = String::from_utf8_lossy(&name[..name_end]).into_owned();
+
+        let mut ff_bits = [0u8; bitmap_len(FF_MAX)];
+        unsafe { eviocgbit_ff(fd, &mut ff_bits) }
+            .map_err(|err| format!("Failed to query supported effects: {err}"))?;
+
+        Ok(DeviceInfo {
+            name,
+            rumble: test_bit(&ff_bits, FF_RUMBLE),
+            periodic: test_bit(&ff_bits, FF_PERIODIC),
+            gain: test_bit(&ff_bits, FF_GAIN),
+            effect_limit: self.effect_limit()?,
+        })
+    }
+
+    /// Remove an uploaded effect, freeing its slot on the device.
+    pub(crate) fn remove(&mut self, id: i16) -> Result<(), String> {
+        let fd = self.device.as_raw_fd();
+        match unsafe { remove_effect(fd, id as u64) } {
             Ok(_) => Ok(()),
             Err(_) => {
                 let last_error = io::Error::last_os_error();
-                let msg = format!("Warn: Failed to remove rumble effect: {last_error}");
-                Err(msg)
+                Err(format!("Failed to remove effect: {last_error}"))
             },
         }
     }
 
-    /// Play a rumble effect.
-    ///
-    /// This will block until the effect has finished playing.
-    ///
-    /// Unsafe wrapper for the purpose of error handling.
-    /// Use [`Self::vibrate`] instead.
-    fn vibrate(&mut self, length: u16, interval: u16, count: u16) -> Result<(), String> {
-        // Ignore without rumble device access.
-        let mut effect = Effect {
-            effect_type: 0x50,
-            id: -1,
-            direction: 0,
-            trigger: Trigger { interval: 0, button: 0 },
-            replay: Replay { length, delay: interval },
-            data: EffectData { rumble: Rumble { strong: u16::MAX, weak: 0 } },
-        };
-
-        // Upload effect to the device.
-        match unsafe { upload_effect(self.device.as_raw_fd(), &mut effect as *const _) } {
-            Err(err) => return Err(format!("Failed to upload rumble effect: {err}")),
-            Ok(_) if effect.id < 0 => return Err(format!("Invalid rumble effect ID: {effect:?}")),
-            Ok(_) => (),
+    /// Ensure `params` is uploaded, reusing the cached effect when nothing
+    /// changed since the last call.
+    fn ensure_uploaded(&mut self, params: EffectParams) -> Result<i16, String> {
+        if self.params == Some(params) {
+            return Ok(self.effect_id);
         }
 
-        // Play effect `count` times.
-        let play = libc::input_event {
-            time: libc::timeval { tv_sec: 0, tv_usec: 0 },
-            code: effect.id as u16,
-            value: count as i32,
-            type_: EV_FF,
-        };
-        let play_ptr = (&play as *const libc::input_event).cast();
-        let play_size = mem::size_of::<libc::input_event>();
-        let play_data = unsafe { slice::from_raw_parts(play_ptr, play_size) };
-        self.device
-            .write(play_data)
-            .map_err(|err| format!("Failed to submit rumble event: {err}"))?;
-
-        // Remove effect after it finished playing.
-        let duration = Duration::from_millis(((length + interval) * count) as u64);
+        let id = self.upload(self.effect_id, params)?;
+        self.effect_id = id;
+        self.params = Some(params);
+
+        Ok(id)
+    }
+
+    /// Play a rumble or periodic waveform effect `count` times.
+    ///
+    /// This will block until the effect has finished playing. `params.strong`
+    /// and `params.weak` set the two rumble motor magnitudes; for a periodic
+    /// waveform, only `strong` is used, as the waveform's amplitude.
+    fn vibrate(&mut self, params: EffectParams, count: u16) -> Result<(), String> {
+        let id = self.ensure_uploaded(params)?;
+        self.replay(id, count)?;
+
+        let duration = Duration::from_millis(((params.length + params.interval) * count) as u64);
         thread::sleep(duration);
-        self.stop(effect.id as u64)?;
 
         Ok(())
     }
 }
 
+impl Drop for Vibrator {
+    fn drop(&mut self) {
+        if self.effect_id >= 0 {
+            let _ = self.remove(self.effect_id);
+        }
+    }
+}
+
 ioctl_write_ptr!(upload_effect, b'E', 0x80, Effect);
 ioctl_write_int!(remove_effect, b'E', 0x81);
+ioctl_read_buf!(eviocgname, b'E', 0x06, u8);
+// `EVIOCGEFFECTS`: number of simultaneous effects the device can hold.
+ioctl_read!(eviocgeffects, b'E', 0x84, i32);
+// `EVIOCGBIT(0, len)`: bitmap of supported event types.
+ioctl_read_buf!(eviocgbit_ev, b'E', 0x20, u8);
+// `EVIOCGBIT(EV_FF, len)`: bitmap of supported force-feedback effects.
+ioctl_read_buf!(eviocgbit_ff, b'E', 0x20 + 0x15, u8);
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
@@ -154,6 +543,7 @@ struct Replay {
 #[derive(Copy, Clone)]
 union EffectData {
     rumble: Rumble,
+    periodic: Periodic,
     #[cfg(target_pointer_width = "64")]
     padding: [u64; 4],
     #[cfg(target_pointer_width = "32")]
@@ -172,3 +562,25 @@ struct Rumble {
     strong: u16,
     weak: u16,
 }
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct Periodic {
+    waveform: u16,
+    period: u16,
+    magnitude: i16,
+    offset: i16,
+    phase: u16,
+    envelope: Envelope,
+    custom_len: u32,
+    custom_data: *mut i16,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct Envelope {
+    attack_length: u16,
+    attack_level: u16,
+    fade_length: u16,
+    fade_level: u16,
+}
@@ -0,0 +1,89 @@
+//! Small pool of concurrently uploaded effects, scheduled by deadline.
+//!
+//! Each slot tracks a remaining play count and the instant it should next
+//! fire, so the pool can compute the minimum deadline across active slots
+//! and tell its caller exactly how long it may wait before an effect needs
+//! to be replayed or retired.
+
+use std::time::{Duration, Instant};
+
+use crate::{EffectParams, Vibrator};
+
+/// A single concurrently-playing effect.
+struct Slot {
+    id: i16,
+    length: u16,
+    interval: u16,
+    remaining: u16,
+    play_at: Instant,
+}
+
+/// Fixed-size pool of effect slots, sized to the device's `EVIOCGEFFECTS`
+/// limit.
+pub(crate) struct Scheduler {
+    slots: Vec<Option<Slot>>,
+}
+
+impl Scheduler {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self { slots: (0..capacity.max(1)).map(|_| None).collect() }
+    }
+
+    /// Upload `params` into a free slot and schedule its first play `delay`
+    /// from now, playing it `count` times in total (at least once).
+    pub(crate) fn schedule(
+        &mut self,
+        vibrator: &mut Vibrator,
+        params: EffectParams,
+        count: u16,
+        delay: Duration,
+    ) -> Result<(), String> {
+        let index = self.slots.iter().position(Option::is_none).ok_or("effect pool full")?;
+
+        let id = vibrator.upload(-1, params)?;
+        let slot = Slot {
+            id,
+            length: params.length,
+            interval: params.interval,
+            remaining: count.max(1),
+            play_at: Instant::now() + delay,
+        };
+        self.slots[index] = Some(slot);
+
+        Ok(())
+    }
+
+    /// Replay and retire every slot whose deadline has passed, returning
+    /// the duration until the next deadline across remaining active slots,
+    /// or `None` if the pool is empty.
+    pub(crate) fn tick(&mut self, vibrator: &mut Vibrator) -> Option<Duration> {
+        let now = Instant::now();
+
+        for slot in self.slots.iter_mut().flatten() {
+            if slot.play_at > now || slot.remaining == 0 {
+                continue;
+            }
+
+            if let Err(err) = vibrator.replay(slot.id, 1) {
+                eprintln!("Warn: Failed to replay effect {}: {err}", slot.id);
+            }
+
+            slot.remaining = slot.remaining.saturating_sub(1);
+            slot.play_at = now + Duration::from_millis((slot.length + slot.interval) as u64);
+        }
+
+        for slot in &mut self.slots {
+            // Wait for the last play to finish, as removing an effect stops it.
+            if !matches!(slot, Some(slot) if slot.remaining == 0 && slot.play_at <= now) {
+                continue;
+            }
+
+            let id = slot.take().expect("checked above").id;
+            if let Err(err) = vibrator.remove(id) {
+                eprintln!("Warn: {err}");
+            }
+        }
+
+        self.slots.iter().flatten().map(|slot| slot.play_at.saturating_duration_since(now)).min()
+    }
+}